@@ -0,0 +1,108 @@
+//! Content-addressed deduplication of findings.
+//!
+//! Two findings are considered the same bug if they reduce to the same test
+//! case and produce the same (normalized) stderr. [`Dedup`] tracks the set of
+//! signatures already saved to disk so that concurrent threads don't clobber
+//! each other's output or save the same bug over and over.
+
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use regex::Regex;
+
+/// Matches hex addresses and line:column locations, which tend to vary
+/// between otherwise-identical crashes.
+pub const DEFAULT_STDERR_NORMALIZE_REGEX: &str = r"0x[0-9a-fA-F]+|:\d+:\d+|:\d+\b";
+
+/// Tracks which finding signatures have already been saved, so that
+/// duplicate crashes are dropped instead of re-saved.
+pub struct Dedup {
+    seen: Mutex<HashSet<u64>>,
+    stderr_normalize: Regex,
+    total: AtomicUsize,
+}
+
+impl Dedup {
+    pub fn new(stderr_normalize: Regex) -> Self {
+        Dedup {
+            seen: Mutex::new(HashSet::new()),
+            stderr_normalize,
+            total: AtomicUsize::new(0),
+        }
+    }
+
+    /// Compute a stable signature for a finding from its reduced test case
+    /// and stderr output, with addresses and line numbers stripped from the
+    /// latter.
+    pub fn signature(&self, reduced: impl AsRef<[u8]>, stderr: impl AsRef<[u8]>) -> u64 {
+        let normalized_stderr = self
+            .stderr_normalize
+            .replace_all(&String::from_utf8_lossy(stderr.as_ref()), "<N>");
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        reduced.as_ref().hash(&mut hasher);
+        normalized_stderr.as_bytes().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Record that a finding occurred (whether or not it's unique) and
+    /// return the running total.
+    pub fn record_finding(&self) -> usize {
+        self.total.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Insert a signature, returning `true` if it hasn't been seen before.
+    pub fn insert(&self, signature: u64) -> bool {
+        self.seen.lock().unwrap().insert(signature)
+    }
+
+    /// Number of distinct signatures seen so far.
+    pub fn unique_count(&self) -> usize {
+        self.seen.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dedup() -> Dedup {
+        Dedup::new(Regex::new(DEFAULT_STDERR_NORMALIZE_REGEX).unwrap())
+    }
+
+    #[test]
+    fn signature_ignores_addresses_and_locations() {
+        let dedup = dedup();
+        let a = dedup.signature(b"crash()", b"panic at 0xdeadbeef, src/lib.rs:42:7");
+        let b = dedup.signature(b"crash()", b"panic at 0xfeedface, src/lib.rs:99:3");
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn signature_differs_on_reduced_or_stderr_shape() {
+        let dedup = dedup();
+        let base = dedup.signature(b"crash()", b"panic: out of bounds");
+        assert_ne!(dedup.signature(b"other()", b"panic: out of bounds"), base);
+        assert_ne!(dedup.signature(b"crash()", b"panic: use after free"), base);
+    }
+
+    #[test]
+    fn insert_reports_first_occurrence_only() {
+        let dedup = dedup();
+        let sig = dedup.signature(b"crash()", b"panic: out of bounds");
+        assert!(dedup.insert(sig));
+        assert!(!dedup.insert(sig));
+        assert_eq!(dedup.unique_count(), 1);
+    }
+
+    #[test]
+    fn record_finding_counts_every_call_including_duplicates() {
+        let dedup = dedup();
+        assert_eq!(dedup.record_finding(), 1);
+        assert_eq!(dedup.record_finding(), 2);
+        assert_eq!(dedup.record_finding(), 3);
+    }
+}
@@ -0,0 +1,110 @@
+//! Loading tree-sitter grammars at runtime via `dlopen`, instead of linking
+//! one fixed grammar into the binary at compile time.
+//!
+//! This lets a single `tree-crasher` binary fuzz any installed grammar
+//! without recompiling, and lets users track upstream grammar revisions
+//! (and tree-sitter itself) independently.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use libloading::Library;
+use libloading::Symbol;
+use tree_sitter::Language;
+
+/// A tree-sitter grammar loaded from a shared object. The library must
+/// outlive the `Language` handle it produced, so we keep it around here.
+/// `language` is declared before `_library` so that field drop order (which
+/// runs top-to-bottom) actually enforces that.
+pub struct DynGrammar {
+    pub language: Language,
+    _library: Library,
+}
+
+/// Derive a grammar's `tree_sitter_<name>` symbol name from the path to its
+/// shared object, e.g. `libtree-sitter-javascript.so` -> `javascript`.
+pub fn grammar_name_from_path(path: &Path) -> Result<String> {
+    let stem = path
+        .file_stem()
+        .and_then(OsStr::to_str)
+        .with_context(|| format!("Couldn't determine grammar name from {}", path.display()))?;
+    Ok(stem
+        .strip_prefix("lib")
+        .unwrap_or(stem)
+        .strip_prefix("tree-sitter-")
+        .unwrap_or(stem)
+        .replace('-', "_"))
+}
+
+/// `dlopen` a grammar shared object, resolve its `tree_sitter_<name>`
+/// constructor symbol, and validate its ABI version against the tree-sitter
+/// this binary is linked against.
+pub fn load(path: &Path, name: &str) -> Result<DynGrammar> {
+    let library = unsafe { Library::new(path) }
+        .with_context(|| format!("Failed to dlopen grammar {}", path.display()))?;
+    let symbol_name = format!("tree_sitter_{name}");
+    let language = unsafe {
+        let constructor: Symbol<unsafe extern "C" fn() -> *const ()> = library
+            .get(symbol_name.as_bytes())
+            .with_context(|| {
+                format!(
+                    "Failed to find symbol {symbol_name} in {} - is --language pointing at the right grammar?",
+                    path.display()
+                )
+            })?;
+        Language::from_raw(constructor())
+    };
+    let abi = language.abi_version();
+    if !(tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION..=tree_sitter::LANGUAGE_VERSION)
+        .contains(&abi)
+    {
+        bail!(
+            "Grammar {} has ABI version {abi}, but this build of tree-crasher supports \
+             versions {}..={}. Rebuild the grammar against a compatible tree-sitter, or \
+             use a tree-crasher build linked against a matching version.",
+            path.display(),
+            tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION,
+            tree_sitter::LANGUAGE_VERSION,
+        );
+    }
+    Ok(DynGrammar {
+        _library: library,
+        language,
+    })
+}
+
+/// Read a grammar's `node-types.json` from disk.
+pub fn read_node_types(path: &Path) -> Result<String> {
+    fs::read_to_string(path)
+        .with_context(|| format!("Failed to read node types file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_lib_prefix_and_tree_sitter_infix() {
+        let name = grammar_name_from_path(Path::new("/usr/lib/libtree-sitter-javascript.so")).unwrap();
+        assert_eq!(name, "javascript");
+    }
+
+    #[test]
+    fn hyphens_become_underscores() {
+        let name = grammar_name_from_path(Path::new("libtree-sitter-c-sharp.so")).unwrap();
+        assert_eq!(name, "c_sharp");
+    }
+
+    #[test]
+    fn tolerates_a_name_with_neither_prefix() {
+        let name = grammar_name_from_path(Path::new("mygrammar.so")).unwrap();
+        assert_eq!(name, "mygrammar");
+    }
+
+    #[test]
+    fn rejects_a_path_with_no_file_stem() {
+        assert!(grammar_name_from_path(Path::new("/")).is_err());
+    }
+}
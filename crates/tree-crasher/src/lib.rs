@@ -3,8 +3,8 @@ use std::fs;
 use std::os::unix::process::ExitStatusExt;
 use std::path::Path;
 use std::path::PathBuf;
+use std::process::ExitStatus;
 use std::time::Duration;
-use std::time::Instant;
 
 use anyhow::{Context, Result};
 use clap::Parser;
@@ -23,6 +23,14 @@ use tree_splicer::splice::{Config, Splicer};
 use treereduce::Check;
 use treereduce::CmdCheck;
 
+mod dedup;
+mod dynload;
+mod novelty;
+mod query;
+mod stats;
+
+use dedup::Dedup;
+
 /// An easy-to-use grammar-based black-box fuzzer
 #[derive(Clone, Debug, clap::Parser)]
 #[command(author, version, about, long_about = None)]
@@ -52,6 +60,27 @@ pub struct Args {
     #[arg(short, long)]
     pub debug: bool,
 
+    /// Query describing what counts as an interesting result, e.g.
+    /// `signal(11) or (exit in [1,2] and stderr ~ "AddressSanitizer" and not
+    /// stderr ~ "LeakSanitizer") or timeout`. Predicates: `exit in [...]`,
+    /// `exit == CODE`, `signal`, `signal(N)`, `timeout`, `stdout ~ REGEX`,
+    /// `stderr ~ REGEX`, `len(stdout|stderr) <op> BYTES`, combined with
+    /// `and`/`or`/`not`/parens. Conflicts with the other interestingness
+    /// flags below, which are sugar for simple queries.
+    #[arg(
+        help_heading = "Interestingness check options",
+        long,
+        value_name = "QUERY",
+        conflicts_with_all = [
+            "interesting_exit_code",
+            "interesting_stdout",
+            "interesting_stderr",
+            "uninteresting_stdout",
+            "uninteresting_stderr",
+        ]
+    )]
+    interesting: Option<String>,
+
     /// Exit code to consider interesting
     #[arg(help_heading = "Interestingness check options",
           long, default_values_t = Vec::<i32>::new(), value_name = "CODE")]
@@ -99,6 +128,41 @@ pub struct Args {
     #[arg(short, long, default_value_os = "tree-crasher.out")]
     pub output: PathBuf,
 
+    /// Regex used to normalize stderr before hashing for deduplication;
+    /// matches are replaced with a placeholder. Defaults to a pattern
+    /// matching hex addresses and line:column locations.
+    #[arg(help_heading = "Deduplication options", long, value_name = "REGEX")]
+    pub dedup_stderr_regex: Option<String>,
+
+    /// Seconds between each stats dashboard report
+    #[arg(
+        help_heading = "Statistics options",
+        long,
+        default_value_t = 10,
+        value_name = "SECS"
+    )]
+    pub stats_interval: u64,
+
+    /// Write a JSON stats snapshot to this path on every report
+    #[arg(help_heading = "Statistics options", long, value_name = "PATH")]
+    pub stats_json: Option<PathBuf>,
+
+    /// Fold mutants with novel behavior (new exit code/signal/output shape)
+    /// back into the seed corpus, to reach deeper states over time without
+    /// coverage instrumentation
+    #[arg(help_heading = "Corpus options", long)]
+    pub novelty: bool,
+
+    /// Maximum corpus size when `--novelty` is enabled; the oldest novel
+    /// entries are evicted first, and seed files are never evicted
+    #[arg(
+        help_heading = "Corpus options",
+        long,
+        default_value_t = 16384,
+        value_name = "N"
+    )]
+    pub max_corpus: usize,
+
     /// Seed
     #[arg(short, long, default_value_t = 0)]
     pub seed: u64,
@@ -124,11 +188,29 @@ pub struct Args {
     pub check: Vec<String>,
 }
 
+/// CLI arguments for the generic `tree-crasher` binary, which loads a
+/// tree-sitter grammar at runtime instead of having one compiled in.
+#[derive(Clone, Debug, clap::Parser)]
+#[command(author, version, about, long_about = None)]
+pub struct DynamicArgs {
+    /// Path to a compiled tree-sitter grammar shared object, e.g.
+    /// libtree-sitter-foo.so
+    #[arg(long, value_name = "PATH")]
+    pub language: PathBuf,
+
+    /// Path to the grammar's node-types.json
+    #[arg(long, value_name = "PATH")]
+    pub node_types: PathBuf,
+
+    #[command(flatten)]
+    pub common: Args,
+}
+
 fn read_file(file: &PathBuf) -> Result<String> {
     fs::read_to_string(file).with_context(|| format!("Failed to read file {}", file.display()))
 }
 
-fn parse(language: &Language, code: &str) -> Result<Tree> {
+pub(crate) fn parse(language: &Language, code: &str) -> Result<Tree> {
     let mut parser = tree_sitter::Parser::new();
     parser
         .set_language(language)
@@ -186,16 +268,112 @@ fn make_check(
     ))
 }
 
+/// Build the interestingness [`query::Query`] that decides which runs count
+/// as findings. `--interesting` takes a full query; otherwise the legacy
+/// `--interesting-exit-code`/`--interesting-stdout`/etc. flags are desugared
+/// into the equivalent query. [`QueryCheck`] wraps this same query as a
+/// [`Check`] so that reduction minimizes against it too.
+fn build_query(args: &Args) -> Result<query::Query> {
+    if let Some(expr) = &args.interesting {
+        return query::Query::parse(expr)
+            .with_context(|| format!("Invalid --interesting query: {expr:?}"));
+    }
+    let stdout_regex = args
+        .interesting_stdout
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .context("Invalid interesting stdout regex")?;
+    let stderr_regex = args
+        .interesting_stderr
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .context("Invalid interesting stderr regex")?;
+    let un_stdout_regex = args
+        .uninteresting_stdout
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .context("Invalid uninteresting stdout regex")?;
+    let un_stderr_regex = args
+        .uninteresting_stderr
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .context("Invalid uninteresting stderr regex")?;
+    let mut exit_codes = args.interesting_exit_code.clone();
+    exit_codes.extend(128..256);
+    Ok(query::Query::from_legacy_flags(
+        &exit_codes,
+        stdout_regex.as_ref(),
+        stderr_regex.as_ref(),
+        un_stdout_regex.as_ref(),
+        un_stderr_regex.as_ref(),
+    ))
+}
+
+/// A [`Check`] that runs the target the same way [`CmdCheck`] does, but
+/// decides interestingness by evaluating a [`query::Query`] against the
+/// result instead of `CmdCheck`'s own fixed exit-code/regex logic. This is
+/// what lets delta-debugging minimize against the exact condition a custom
+/// `--interesting` query describes, rather than just "exited with a signal
+/// or a code in 128..256".
+#[derive(Clone)]
+struct QueryCheck {
+    cmd: CmdCheck,
+    query: query::Query,
+}
+
+impl Check for QueryCheck {
+    type State = <CmdCheck as Check>::State;
+
+    fn start(&self, input: &[u8]) -> Result<Self::State> {
+        self.cmd.start(input)
+    }
+
+    fn wait_with_output(&self, state: Self::State) -> Result<(bool, Option<ExitStatus>, Vec<u8>, Vec<u8>)> {
+        let (_interesting, status, stdout, stderr) = self.cmd.wait_with_output(state)?;
+        let result = query::ProcessResult {
+            exit_code: status.and_then(|s| s.code()),
+            signal: status.and_then(|s| s.signal()),
+            timed_out: status.is_none(),
+            stdout: &stdout,
+            stderr: &stderr,
+        };
+        let interesting = self.query.eval(&result);
+        Ok((interesting, status, stdout, stderr))
+    }
+}
+
+/// Cross-cutting state threaded through every [`check`]/[`job`] call: the
+/// interestingness query, finding dedup state, live stats counters, and
+/// (optionally) the output-novelty corpus-growth state. Bundled into one
+/// struct so that wiring in another subsystem doesn't mean yet another
+/// positional parameter on `check`/`job`.
+#[derive(Clone, Copy)]
+struct FuzzContext<'a> {
+    query: &'a query::Query,
+    dedup: &'a Dedup,
+    stats: &'a stats::Stats,
+    novelty: Option<&'a novelty::NoveltySet>,
+}
+
 const BATCH: usize = 100_000; // not all materialized at once
 
+#[allow(clippy::too_many_arguments)]
 fn check(
     language: &Language,
     node_types: &treereduce::NodeTypes,
     output: &Path,
     chk: &CmdCheck,
+    ctx: &FuzzContext,
+    pending_novel: &mut Vec<Vec<u8>>,
+    thread_idx: usize,
     inp: &[u8],
 ) -> i32 {
     trace!("checking input {}", String::from_utf8_lossy(inp));
+    ctx.stats.record_exec(thread_idx);
     let state = match chk.start(inp) {
         Ok(s) => s,
         Err(e) => {
@@ -203,10 +381,33 @@ fn check(
             return -1;
         }
     };
-    let (interesting, status, stdout, stderr) = chk.wait_with_output(state).unwrap();
-    let code = status.and_then(|s| s.code()).unwrap_or(-1);
+    let (_interesting, status, stdout, stderr) = chk.wait_with_output(state).unwrap();
+    let code_opt = status.and_then(|s| s.code());
+    let code = code_opt.unwrap_or(-1);
     let sig = status.and_then(|s| s.signal());
-    if interesting || sig.is_some() {
+    let timed_out = status.is_none();
+    if timed_out {
+        ctx.stats.record_timeout();
+    }
+    let result = query::ProcessResult {
+        exit_code: code_opt,
+        signal: sig,
+        timed_out,
+        stdout: &stdout,
+        stderr: &stderr,
+    };
+    if let Some(novelty_set) = ctx.novelty {
+        let fp = novelty::fingerprint(&result);
+        if novelty_set.is_novel(fp) {
+            // Don't insert into the corpus here: the caller may be holding a
+            // read guard on `Corpus::files` (e.g. from inside `with_files`),
+            // and `Corpus::try_insert` takes that lock for writing. Instead,
+            // stash novel inputs and let the caller insert them once it's no
+            // longer holding any read guard.
+            pending_novel.push(inp.to_vec());
+        }
+    }
+    if ctx.query.eval(&result) {
         if let Some(s) = sig {
             if s == 6 {
                 return code;
@@ -215,35 +416,54 @@ fn check(
         } else {
             info!("interesting!");
         }
-        let mut rng = rand::rng();
-        let i = rng.random_range(0..10192);
-        fs::write(output.join(format!("tree-crasher-{i}.out")), inp).unwrap();
-        fs::write(output.join(format!("tree-crasher-{i}.stdout")), stdout).unwrap();
-        fs::write(output.join(format!("tree-crasher-{i}.stderr")), stderr).unwrap();
-        let tree = parse(language, &String::from_utf8_lossy(inp)).unwrap();
-        match treereduce::treereduce_multi_pass(
-            language.clone(),
-            node_types,
-            treereduce::Original::new(tree, inp.to_vec()),
-            &treereduce::Config {
-                check: chk.clone(),
-                delete_non_optional: true,
-                jobs: 1,
-                min_reduction: 2,
-                replacements: HashMap::new(),
-            },
-            Some(8),
-        ) {
-            Err(e) => warn!("Failed to reduce! {e}"),
-            Ok((reduced, _)) => {
-                fs::write(format!("tree-crasher-{i}.reduced.out"), reduced.text).unwrap();
+        let reduced = match parse(language, &String::from_utf8_lossy(inp)) {
+            Err(e) => {
+                warn!("Failed to parse finding for reduction! {e}");
+                ctx.stats.record_parse_failure();
+                None
             }
+            Ok(tree) => match treereduce::treereduce_multi_pass(
+                language.clone(),
+                node_types,
+                treereduce::Original::new(tree, inp.to_vec()),
+                &treereduce::Config {
+                    check: QueryCheck { cmd: chk.clone(), query: ctx.query.clone() },
+                    delete_non_optional: true,
+                    jobs: 1,
+                    min_reduction: 2,
+                    replacements: HashMap::new(),
+                },
+                Some(8),
+            ) {
+                Err(e) => {
+                    warn!("Failed to reduce! {e}");
+                    None
+                }
+                Ok((reduced, _)) => Some(reduced.text),
+            },
+        };
+        let reduced_bytes: &[u8] = reduced.as_ref().map_or(inp, |t| t.as_ref());
+        let total = ctx.dedup.record_finding();
+        ctx.stats.record_finding();
+        let signature = ctx.dedup.signature(reduced_bytes, &stderr);
+        if ctx.dedup.insert(signature) {
+            let unique = ctx.dedup.unique_count();
+            ctx.stats.record_unique_finding();
+            info!("finding: {unique} unique / {total} total");
+            let dir = output.join(format!("{signature:016x}"));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("orig"), inp).unwrap();
+            fs::write(dir.join("reduced"), reduced_bytes).unwrap();
+            fs::write(dir.join("stdout"), stdout).unwrap();
+            fs::write(dir.join("stderr"), stderr).unwrap();
+        } else {
+            debug!("dropping duplicate finding with signature {signature:016x}");
         }
     }
     code
 }
 
-// TODO: print executions/sec
+#[allow(clippy::too_many_arguments)]
 fn job(
     thread_idx: usize,
     language: Language,
@@ -251,10 +471,11 @@ fn job(
     node_types1: &treereduce::NodeTypes,
     node_types2: &tree_splicer::node_types::NodeTypes,
     args: &Args,
-    files: &HashMap<String, (Vec<u8>, Tree)>,
+    corpus: &novelty::Corpus,
     chk: CmdCheck,
+    ctx: &FuzzContext,
 ) {
-    if files.is_empty() {
+    if corpus.len() == 0 {
         error!("No files provided.");
         return;
     }
@@ -262,14 +483,13 @@ fn job(
     if args.radamsa {
         unsafe { radamsa_sys::radamsa_init() };
         let mut rng = rand::rng();
-        let file_bytes: Vec<_> = files.values().map(|(bytes, _tree)| bytes).collect();
+        let file_bytes: Vec<Vec<u8>> =
+            corpus.with_files(|files| files.values().map(|(bytes, _tree)| bytes.clone()).collect());
+        let mut pending_novel = Vec::new();
         loop {
             const MAX_SIZE: usize = 4096;
             // TODO: Mutate in-place
-            let mut input: Vec<u8> = file_bytes
-                .get(rng.random_range(0..files.len()))
-                .unwrap()
-                .to_vec();
+            let mut input: Vec<u8> = file_bytes.get(rng.random_range(0..file_bytes.len())).unwrap().clone();
             let mut mutant = vec![0u8; MAX_SIZE];
             let out_len = unsafe {
                 radamsa_sys::radamsa(
@@ -282,7 +502,24 @@ fn job(
             };
             assert!(out_len <= MAX_SIZE);
             mutant.truncate(out_len);
-            check(&language, node_types1, &args.output, &chk, &mutant);
+            check(
+                &language,
+                node_types1,
+                &args.output,
+                &chk,
+                ctx,
+                &mut pending_novel,
+                thread_idx,
+                &mutant,
+            );
+            // Safe to insert here: unlike the splicing loop below, this loop
+            // never holds a read guard on `corpus.files` while running.
+            if !pending_novel.is_empty() {
+                for bytes in pending_novel.drain(..) {
+                    corpus.try_insert(&language, bytes);
+                }
+                ctx.stats.set_corpus_size(corpus.len());
+            }
         }
     }
     let mut rng = <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(
@@ -300,24 +537,40 @@ fn job(
             reparse: usize::MAX,
             seed: rng.next_u64(),
         };
-        let start = Instant::now();
-        let mut execs = 0;
-        if let Some(splicer) = Splicer::new(config, files) {
+        let mut pending_novel = Vec::new();
+        let had_splices = corpus.with_files(|files| {
+            let Some(splicer) = Splicer::new(config, files) else {
+                return false;
+            };
             for (i, out) in splicer.enumerate() {
                 debug!("thread {thread_idx} iteration {iter} test case {i}");
                 if i == BATCH {
                     break;
                 }
-                let _code = check(&language, node_types1, &args.output, &chk, &out);
-                execs += 1;
-                let secs = start.elapsed().as_secs();
-                if secs > 0 && ((iter == 1 && execs % 500 == 0) || (execs % 10_000 == 0)) {
-                    info!("execs/sec: {}", execs / secs);
-                }
+                let _code = check(
+                    &language,
+                    node_types1,
+                    &args.output,
+                    &chk,
+                    ctx,
+                    &mut pending_novel,
+                    thread_idx,
+                    &out,
+                );
             }
-        } else {
+            true
+        });
+        if !had_splices {
             error!("error: no splices!"); // TODO: improve message
         }
+        // `with_files` has returned and released its read guard by now, so
+        // it's safe for `try_insert` to take the write lock.
+        if !pending_novel.is_empty() {
+            for bytes in pending_novel.drain(..) {
+                corpus.try_insert(&language, bytes);
+            }
+            ctx.stats.set_corpus_size(corpus.len());
+        }
     }
 }
 
@@ -349,9 +602,27 @@ fn init_tracing(cli: &Args) {
 // TODO: graceful exit
 pub fn main(language: Language, node_types_json_str: &'static str) -> Result<()> {
     let args = Args::parse();
+    init_tracing(&args);
+    run(args, language, node_types_json_str)
+}
+
+/// Fuzz a tree-sitter grammar loaded at runtime via `dlopen`, rather than one
+/// compiled into the binary. See [`dynload`].
+pub fn main_dynamic() -> Result<()> {
+    let args = DynamicArgs::parse();
+    init_tracing(&args.common);
+
+    debug!("Loading grammar {}...", args.language.display());
+    let name = dynload::grammar_name_from_path(&args.language)?;
+    let grammar = dynload::load(&args.language, &name)?;
+    let node_types_json = dynload::read_node_types(&args.node_types)?;
+
+    run(args.common, grammar.language, &node_types_json)
+}
+
+fn run(args: Args, language: Language, node_types_json_str: &str) -> Result<()> {
     debug_assert!(args.interesting_stdout.is_some() || args.uninteresting_stdout.is_none());
     debug_assert!(args.interesting_stderr.is_some() || args.uninteresting_stderr.is_none());
-    init_tracing(&args);
 
     debug!("Loading testcases...");
     let mut files = HashMap::new();
@@ -376,8 +647,18 @@ pub fn main(language: Language, node_types_json_str: &'static str) -> Result<()>
         args.uninteresting_stdout.clone(),
         args.uninteresting_stderr.clone(),
     )?;
+    let query = build_query(&args)?;
     let node_types1 = treereduce::NodeTypes::new(node_types_json_str).unwrap();
     let node_types2 = tree_splicer::node_types::NodeTypes::new(node_types_json_str).unwrap();
+    let dedup_stderr_regex = Regex::new(
+        args.dedup_stderr_regex
+            .as_deref()
+            .unwrap_or(dedup::DEFAULT_STDERR_NORMALIZE_REGEX),
+    )
+    .context("Invalid dedup stderr regex")?;
+    let dedup = Dedup::new(dedup_stderr_regex);
+    let corpus = novelty::Corpus::new(files, args.max_corpus);
+    let novelty_set = args.novelty.then(novelty::NoveltySet::new);
 
     fs::create_dir_all(&args.output)
         .with_context(|| format!("When creating output directory {}", args.output.display()))?;
@@ -394,16 +675,25 @@ pub fn main(language: Language, node_types_json_str: &'static str) -> Result<()>
     } else {
         args.jobs
     };
+    let stats = stats::Stats::new(jobs);
+    stats.set_corpus_size(corpus.len());
+    let ctx = FuzzContext { query: &query, dedup: &dedup, stats: &stats, novelty: novelty_set.as_ref() };
     std::thread::scope(|s| {
+        stats::spawn_reporter(
+            s,
+            &stats,
+            Duration::from_secs(args.stats_interval),
+            args.stats_json.as_deref(),
+        );
         for i in 0..jobs {
             let language = language.clone();
             let chk = chk.clone();
             let node_types1 = &node_types1;
             let node_types2 = &node_types2;
             let args = &args;
-            let files = &files;
+            let corpus = &corpus;
             s.spawn(move || {
-                job(i, language, node_types1, node_types2, args, files, chk);
+                job(i, language, node_types1, node_types2, args, corpus, chk, &ctx);
             });
         }
     });
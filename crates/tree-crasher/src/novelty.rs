@@ -0,0 +1,175 @@
+//! An optional output-novelty feedback loop.
+//!
+//! tree-crasher is black-box and otherwise never feeds successful mutations
+//! back into its seed pool - the corpus stays fixed for the whole run. When
+//! enabled, mutants whose behavioral fingerprint (derived only from
+//! observable exit status and output, no coverage instrumentation) hasn't
+//! been seen before are folded into the shared corpus, so later splices can
+//! build on them. This approximates coverage-guided fuzzing's corpus growth
+//! using only observable program output.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use tree_sitter::Language;
+use tree_sitter::Tree;
+
+use crate::query::ProcessResult;
+
+/// Number of leading stderr lines considered when fingerprinting.
+const FINGERPRINT_STDERR_LINES: usize = 8;
+
+/// Coarse log2 length bucket, so near-identical lengths fingerprint the same.
+fn len_bucket(len: usize) -> u32 {
+    if len == 0 { 0 } else { usize::BITS - len.leading_zeros() }
+}
+
+/// Masks decimal digits out of a line so varying line numbers (and addresses
+/// that differ only in digits) don't make two otherwise-identical behaviors
+/// look novel. Hex letters are kept, so addresses that differ in a letter
+/// still fingerprint differently.
+fn mask_digits(s: &str) -> String {
+    s.chars().map(|c| if c.is_ascii_digit() { '#' } else { c }).collect()
+}
+
+/// A cheap behavioral fingerprint of one run: bucketed stdout/stderr length,
+/// exit code, signal, and a hash of the first few stderr lines with digits
+/// masked out.
+pub fn fingerprint(result: &ProcessResult) -> u64 {
+    let stderr_head: String = String::from_utf8_lossy(result.stderr)
+        .lines()
+        .take(FINGERPRINT_STDERR_LINES)
+        .map(mask_digits)
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    result.exit_code.hash(&mut hasher);
+    result.signal.hash(&mut hasher);
+    len_bucket(result.stdout.len()).hash(&mut hasher);
+    len_bucket(result.stderr.len()).hash(&mut hasher);
+    stderr_head.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Tracks which behavioral fingerprints have been seen so far.
+pub struct NoveltySet {
+    seen: Mutex<HashSet<u64>>,
+}
+
+impl NoveltySet {
+    pub fn new() -> Self {
+        NoveltySet { seen: Mutex::new(HashSet::new()) }
+    }
+
+    /// Returns `true` the first time a given fingerprint is seen.
+    pub fn is_novel(&self, fp: u64) -> bool {
+        self.seen.lock().unwrap().insert(fp)
+    }
+}
+
+impl Default for NoveltySet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The shared seed corpus that `Splicer` configs draw from, grown at
+/// runtime by folding in mutants with novel behavior. Bounded by
+/// `max_corpus`, evicting the oldest *novel* entry (FIFO) once full - the
+/// user's original seed files are never evicted, no matter how long the
+/// run goes on, since they're the only files guaranteed to parse and to
+/// cover whatever the user cared enough about to seed with.
+pub struct Corpus {
+    files: RwLock<HashMap<String, (Vec<u8>, Tree)>>,
+    /// FIFO queue of evictable keys, i.e. ones `try_insert` added itself.
+    /// The initial seed corpus is deliberately never pushed onto this.
+    insertion_order: Mutex<VecDeque<String>>,
+    max_corpus: usize,
+    next_id: AtomicUsize,
+}
+
+impl Corpus {
+    pub fn new(initial: HashMap<String, (Vec<u8>, Tree)>, max_corpus: usize) -> Self {
+        Corpus {
+            files: RwLock::new(initial),
+            insertion_order: Mutex::new(VecDeque::new()),
+            max_corpus,
+            next_id: AtomicUsize::new(0),
+        }
+    }
+
+    /// Run `with` against a read-only snapshot of the current corpus.
+    pub fn with_files<R>(&self, with: impl FnOnce(&HashMap<String, (Vec<u8>, Tree)>) -> R) -> R {
+        with(&self.files.read().unwrap())
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.read().unwrap().len()
+    }
+
+    /// Parse `bytes` and fold it into the corpus, evicting the oldest novel
+    /// mutant first if already at `max_corpus`. If `max_corpus` has already
+    /// been reached by seed files alone, there's nothing evictable, so the
+    /// corpus is allowed to grow past `max_corpus` rather than touch a seed.
+    pub fn try_insert(&self, language: &Language, bytes: Vec<u8>) {
+        let Ok(tree) = crate::parse(language, &String::from_utf8_lossy(&bytes)) else {
+            return;
+        };
+        let key = format!("novel-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut files = self.files.write().unwrap();
+        let mut insertion_order = self.insertion_order.lock().unwrap();
+        if self.max_corpus > 0 && files.len() >= self.max_corpus {
+            if let Some(oldest) = insertion_order.pop_front() {
+                files.remove(&oldest);
+            }
+        }
+        insertion_order.push_back(key.clone());
+        files.insert(key, (bytes, tree));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result<'a>(exit_code: Option<i32>, stderr: &'a [u8]) -> ProcessResult<'a> {
+        ProcessResult { exit_code, signal: None, timed_out: false, stdout: b"", stderr }
+    }
+
+    #[test]
+    fn len_bucket_groups_nearby_lengths() {
+        assert_eq!(len_bucket(0), 0);
+        assert_eq!(len_bucket(100), len_bucket(127));
+        assert_ne!(len_bucket(127), len_bucket(128));
+    }
+
+    #[test]
+    fn mask_digits_replaces_only_digits() {
+        assert_eq!(mask_digits("addr 0x1a2b at line 42"), "addr #x#a#b at line ##");
+    }
+
+    #[test]
+    fn fingerprint_ignores_varying_addresses_but_not_shape() {
+        let a = result(Some(1), b"SEGV at 0x12345678\nframe #0");
+        let b = result(Some(1), b"SEGV at 0x87654321\nframe #1");
+        assert_eq!(fingerprint(&a), fingerprint(&b));
+
+        let c = result(Some(1), b"a completely different crash");
+        assert_ne!(fingerprint(&a), fingerprint(&c));
+    }
+
+    #[test]
+    fn novelty_set_flags_each_fingerprint_once() {
+        let set = NoveltySet::new();
+        assert!(set.is_novel(1));
+        assert!(!set.is_novel(1));
+        assert!(set.is_novel(2));
+    }
+}
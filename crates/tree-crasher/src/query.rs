@@ -0,0 +1,485 @@
+//! A small boolean query language for describing what counts as an
+//! "interesting" process result, e.g.
+//!
+//! ```text
+//! signal(11) or (exit in [1,2] and stderr ~ "AddressSanitizer" and not stderr ~ "LeakSanitizer") or timeout
+//! ```
+//!
+//! This replaces the fixed conjunction that [`crate::check`] used to rely
+//! on, built by `make_check` out of `--interesting-exit-code`/`--interesting-stdout`/etc. with an AST
+//! that can combine predicates freely. The old flags still work; they're
+//! desugared into the same AST by [`Query::from_legacy_flags`].
+
+use anyhow::Context;
+use anyhow::Result;
+use anyhow::bail;
+use regex::Regex;
+
+/// The observable outcome of one run of the target.
+#[derive(Debug)]
+pub struct ProcessResult<'a> {
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub timed_out: bool,
+    pub stdout: &'a [u8],
+    pub stderr: &'a [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LenOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+}
+
+impl LenOp {
+    fn eval(self, len: usize, bytes: usize) -> bool {
+        match self {
+            LenOp::Lt => len < bytes,
+            LenOp::Le => len <= bytes,
+            LenOp::Gt => len > bytes,
+            LenOp::Ge => len >= bytes,
+            LenOp::Eq => len == bytes,
+        }
+    }
+}
+
+/// A predicate over a [`ProcessResult`], or a boolean combination thereof.
+#[derive(Clone, Debug)]
+pub enum Query {
+    /// Matches if the exit code is in this list.
+    ExitIn(Vec<i32>),
+    /// Matches if killed by this signal, or by any signal when `None`.
+    Signal(Option<i32>),
+    Timeout,
+    StdoutMatches(Regex),
+    StderrMatches(Regex),
+    Len { stream: Stream, op: LenOp, bytes: usize },
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+}
+
+impl Query {
+    /// Parse a query expression.
+    pub fn parse(input: &str) -> Result<Query> {
+        let tokens = lex(input)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0 };
+        let query = parser.parse_or()?;
+        if parser.pos != tokens.len() {
+            bail!(
+                "Unexpected trailing input in interestingness query, near token {:?}",
+                parser.tokens[parser.pos]
+            );
+        }
+        Ok(query)
+    }
+
+    /// Desugar the legacy `--interesting-exit-code`/`--interesting-stdout`/
+    /// `--interesting-stderr`/`--uninteresting-*` flags into the equivalent
+    /// query, for scripts that predate the `--interesting` DSL: killed by any
+    /// signal, or exited with one of `exit_codes` while every given regex
+    /// matches (and its uninteresting counterpart, if any, doesn't).
+    pub fn from_legacy_flags(
+        exit_codes: &[i32],
+        stdout: Option<&Regex>,
+        stderr: Option<&Regex>,
+        uninteresting_stdout: Option<&Regex>,
+        uninteresting_stderr: Option<&Regex>,
+    ) -> Query {
+        let mut matched = Query::ExitIn(exit_codes.to_vec());
+        if let Some(re) = stdout {
+            let clause = Self::legacy_regex_clause(
+                Query::StdoutMatches(re.clone()),
+                uninteresting_stdout,
+                Stream::Stdout,
+            );
+            matched = Query::And(Box::new(matched), Box::new(clause));
+        }
+        if let Some(re) = stderr {
+            let clause = Self::legacy_regex_clause(
+                Query::StderrMatches(re.clone()),
+                uninteresting_stderr,
+                Stream::Stderr,
+            );
+            matched = Query::And(Box::new(matched), Box::new(clause));
+        }
+        Query::Or(Box::new(Query::Signal(None)), Box::new(matched))
+    }
+
+    fn legacy_regex_clause(matches: Query, uninteresting: Option<&Regex>, stream: Stream) -> Query {
+        match uninteresting {
+            None => matches,
+            Some(un) => {
+                let un_matches = match stream {
+                    Stream::Stdout => Query::StdoutMatches(un.clone()),
+                    Stream::Stderr => Query::StderrMatches(un.clone()),
+                };
+                Query::And(Box::new(matches), Box::new(Query::Not(Box::new(un_matches))))
+            }
+        }
+    }
+
+    /// Evaluate this query against a process result.
+    pub fn eval(&self, result: &ProcessResult) -> bool {
+        match self {
+            Query::ExitIn(codes) => result.exit_code.is_some_and(|c| codes.contains(&c)),
+            Query::Signal(None) => result.signal.is_some(),
+            Query::Signal(Some(s)) => result.signal == Some(*s),
+            Query::Timeout => result.timed_out,
+            Query::StdoutMatches(re) => re.is_match(&String::from_utf8_lossy(result.stdout)),
+            Query::StderrMatches(re) => re.is_match(&String::from_utf8_lossy(result.stderr)),
+            Query::Len { stream, op, bytes } => {
+                let len = match stream {
+                    Stream::Stdout => result.stdout.len(),
+                    Stream::Stderr => result.stderr.len(),
+                };
+                op.eval(len, *bytes)
+            }
+            Query::And(l, r) => l.eval(result) && r.eval(result),
+            Query::Or(l, r) => l.eval(result) || r.eval(result),
+            Query::Not(q) => !q.eval(result),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Tilde,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    EqEq,
+    Int(i64),
+    Str(String),
+    Ident(String),
+}
+
+fn lex(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = input.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            ' ' | '\t' | '\n' | '\r' => i += 1,
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            '[' => {
+                tokens.push(Token::LBracket);
+                i += 1;
+            }
+            ']' => {
+                tokens.push(Token::RBracket);
+                i += 1;
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                i += 1;
+            }
+            '~' => {
+                tokens.push(Token::Tilde);
+                i += 1;
+            }
+            '<' => {
+                i += 1;
+                if chars.get(i) == Some(&'=') {
+                    i += 1;
+                    tokens.push(Token::Le);
+                } else {
+                    tokens.push(Token::Lt);
+                }
+            }
+            '>' => {
+                i += 1;
+                if chars.get(i) == Some(&'=') {
+                    i += 1;
+                    tokens.push(Token::Ge);
+                } else {
+                    tokens.push(Token::Gt);
+                }
+            }
+            '=' => {
+                i += 1;
+                if chars.get(i) == Some(&'=') {
+                    i += 1;
+                    tokens.push(Token::EqEq);
+                } else {
+                    bail!("Unexpected '=' in interestingness query; did you mean '=='?");
+                }
+            }
+            '"' => {
+                i += 1;
+                let mut s = String::new();
+                loop {
+                    match chars.get(i) {
+                        None => bail!("Unterminated string literal in interestingness query"),
+                        Some('"') => {
+                            i += 1;
+                            break;
+                        }
+                        Some('\\') => {
+                            i += 1;
+                            // Only `\"` and `\\` are escapes; anything else
+                            // (e.g. `\d`, `\s`, `\.`) passes through with the
+                            // backslash intact, so regex escapes in string
+                            // literals reach `Regex::new` unharmed.
+                            match chars.get(i) {
+                                Some('"') => {
+                                    s.push('"');
+                                    i += 1;
+                                }
+                                Some('\\') => {
+                                    s.push('\\');
+                                    i += 1;
+                                }
+                                Some(&other) => {
+                                    s.push('\\');
+                                    s.push(other);
+                                    i += 1;
+                                }
+                                None => bail!("Unterminated string literal in interestingness query"),
+                            }
+                        }
+                        Some(&ch) => {
+                            s.push(ch);
+                            i += 1;
+                        }
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            c if c.is_ascii_digit() => {
+                let start = i;
+                while chars.get(i).is_some_and(char::is_ascii_digit) {
+                    i += 1;
+                }
+                let s: String = chars[start..i].iter().collect();
+                tokens.push(Token::Int(
+                    s.parse().with_context(|| format!("Invalid integer {s:?}"))?,
+                ));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while chars.get(i).is_some_and(|c| c.is_alphanumeric() || *c == '_') {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            }
+            c => bail!("Unexpected character {c:?} in interestingness query"),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn bump(&mut self) -> Option<&Token> {
+        let tok = self.tokens.get(self.pos);
+        self.pos += 1;
+        tok
+    }
+
+    fn eat_ident(&mut self, kw: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(i)) if i == kw) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, tok: &Token) -> Result<()> {
+        if self.peek() == Some(tok) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            bail!("Expected {tok:?} in interestingness query, found {:?}", self.peek())
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Query> {
+        let mut lhs = self.parse_and()?;
+        while self.eat_ident("or") {
+            let rhs = self.parse_and()?;
+            lhs = Query::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Query> {
+        let mut lhs = self.parse_unary()?;
+        while self.eat_ident("and") {
+            let rhs = self.parse_unary()?;
+            lhs = Query::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Query> {
+        if self.eat_ident("not") {
+            return Ok(Query::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Query> {
+        match self.bump().cloned() {
+            Some(Token::LParen) => {
+                let q = self.parse_or()?;
+                self.expect(&Token::RParen)?;
+                Ok(q)
+            }
+            Some(Token::Ident(kw)) => match kw.as_str() {
+                "timeout" => Ok(Query::Timeout),
+                "signal" => {
+                    if matches!(self.peek(), Some(Token::LParen)) {
+                        self.pos += 1;
+                        let n = self.parse_int()?;
+                        self.expect(&Token::RParen)?;
+                        Ok(Query::Signal(Some(n as i32)))
+                    } else {
+                        Ok(Query::Signal(None))
+                    }
+                }
+                "exit" => {
+                    if self.eat_ident("in") {
+                        self.expect(&Token::LBracket)?;
+                        let mut codes = vec![self.parse_int()? as i32];
+                        while self.peek() == Some(&Token::Comma) {
+                            self.pos += 1;
+                            codes.push(self.parse_int()? as i32);
+                        }
+                        self.expect(&Token::RBracket)?;
+                        Ok(Query::ExitIn(codes))
+                    } else {
+                        self.expect(&Token::EqEq)?;
+                        Ok(Query::ExitIn(vec![self.parse_int()? as i32]))
+                    }
+                }
+                "stdout" | "stderr" => {
+                    self.expect(&Token::Tilde)?;
+                    let pat = self.parse_string()?;
+                    let re = Regex::new(&pat).with_context(|| format!("Invalid regex {pat:?}"))?;
+                    Ok(if kw == "stdout" {
+                        Query::StdoutMatches(re)
+                    } else {
+                        Query::StderrMatches(re)
+                    })
+                }
+                "len" => {
+                    self.expect(&Token::LParen)?;
+                    let stream = match self.bump() {
+                        Some(Token::Ident(s)) if s == "stdout" => Stream::Stdout,
+                        Some(Token::Ident(s)) if s == "stderr" => Stream::Stderr,
+                        other => bail!("Expected 'stdout' or 'stderr' in len(...), found {other:?}"),
+                    };
+                    self.expect(&Token::RParen)?;
+                    let op = match self.bump() {
+                        Some(Token::Lt) => LenOp::Lt,
+                        Some(Token::Le) => LenOp::Le,
+                        Some(Token::Gt) => LenOp::Gt,
+                        Some(Token::Ge) => LenOp::Ge,
+                        Some(Token::EqEq) => LenOp::Eq,
+                        other => bail!("Expected a comparison after len(...), found {other:?}"),
+                    };
+                    let bytes = self.parse_int()?;
+                    Ok(Query::Len { stream, op, bytes: bytes as usize })
+                }
+                other => bail!("Unknown predicate {other:?} in interestingness query"),
+            },
+            other => bail!("Expected a predicate or '(' in interestingness query, found {other:?}"),
+        }
+    }
+
+    fn parse_int(&mut self) -> Result<i64> {
+        match self.bump() {
+            Some(Token::Int(n)) => Ok(*n),
+            other => bail!("Expected an integer in interestingness query, found {other:?}"),
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String> {
+        match self.bump() {
+            Some(Token::Str(s)) => Ok(s.clone()),
+            other => bail!("Expected a string literal in interestingness query, found {other:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result<'a>(exit_code: Option<i32>, stdout: &'a [u8], stderr: &'a [u8]) -> ProcessResult<'a> {
+        ProcessResult { exit_code, signal: None, timed_out: false, stdout, stderr }
+    }
+
+    #[test]
+    fn regex_escapes_survive_parsing() {
+        let query = Query::parse(r#"stderr ~ "\d+ bytes""#).unwrap();
+        assert!(query.eval(&result(None, b"", b"heap-buffer-overflow: 42 bytes")));
+        assert!(!query.eval(&result(None, b"", b"heap-buffer-overflow: bytes")));
+    }
+
+    #[test]
+    fn escaped_dot_is_literal() {
+        let query = Query::parse(r#"stderr ~ "a\.b""#).unwrap();
+        assert!(query.eval(&result(None, b"", b"a.b")));
+        assert!(!query.eval(&result(None, b"", b"axb")));
+    }
+
+    #[test]
+    fn string_escape_passthrough() {
+        // `\d`, `\.`, `\s` aren't escapes the lexer knows about, so they must
+        // reach the token unchanged; only `\\` and `\"` are collapsed.
+        let tokens = lex(r#"stdout ~ "\d+\.\s\\foo\"bar""#).unwrap();
+        match &tokens[2] {
+            Token::Str(s) => assert_eq!(s, "\\d+\\.\\s\\foo\"bar"),
+            other => panic!("expected a Str token, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn and_or_not_combine_predicates() {
+        let query = Query::parse("signal(11) or (exit in [1,2] and not stderr ~ \"ignore\")").unwrap();
+        assert!(query.eval(&ProcessResult {
+            exit_code: None,
+            signal: Some(11),
+            timed_out: false,
+            stdout: b"",
+            stderr: b"",
+        }));
+        assert!(query.eval(&result(Some(1), b"", b"")));
+        assert!(!query.eval(&result(Some(1), b"", b"ignore")));
+        assert!(!query.eval(&result(Some(3), b"", b"")));
+    }
+}
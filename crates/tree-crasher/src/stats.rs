@@ -0,0 +1,150 @@
+//! Live statistics: worker threads update shared atomic counters, and a
+//! background reporter thread periodically logs an aggregated dashboard
+//! and, optionally, writes the same snapshot to disk as JSON for external
+//! monitoring of long-running campaigns.
+
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::Serialize;
+use tracing::debug;
+use tracing::info;
+use tracing::warn;
+
+/// Counters updated by worker threads as they run; aggregated and reported
+/// by [`spawn_reporter`].
+pub struct Stats {
+    execs: Vec<AtomicU64>,
+    parse_failures: AtomicU64,
+    timeouts: AtomicU64,
+    findings: AtomicU64,
+    unique_findings: AtomicU64,
+    corpus_size: AtomicU64,
+    start: Instant,
+}
+
+impl Stats {
+    pub fn new(threads: usize) -> Self {
+        Stats {
+            execs: (0..threads).map(|_| AtomicU64::new(0)).collect(),
+            parse_failures: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+            findings: AtomicU64::new(0),
+            unique_findings: AtomicU64::new(0),
+            corpus_size: AtomicU64::new(0),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn record_exec(&self, thread_idx: usize) {
+        self.execs[thread_idx].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_parse_failure(&self) {
+        self.parse_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_timeout(&self) {
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_finding(&self) {
+        self.findings.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_unique_finding(&self) {
+        self.unique_findings.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_corpus_size(&self, n: usize) {
+        self.corpus_size.store(n as u64, Ordering::Relaxed);
+    }
+
+    fn total_execs(&self) -> u64 {
+        self.execs.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let elapsed_secs = self.start.elapsed().as_secs_f64();
+        let total_execs = self.total_execs();
+        let execs_per_sec = if elapsed_secs > 0.0 { total_execs as f64 / elapsed_secs } else { 0.0 };
+        let per_thread_execs: Vec<u64> = self.execs.iter().map(|c| c.load(Ordering::Relaxed)).collect();
+        let per_thread_execs_per_sec = per_thread_execs
+            .iter()
+            .map(|&n| if elapsed_secs > 0.0 { n as f64 / elapsed_secs } else { 0.0 })
+            .collect();
+        Snapshot {
+            elapsed_secs,
+            total_execs,
+            execs_per_sec,
+            per_thread_execs,
+            per_thread_execs_per_sec,
+            parse_failures: self.parse_failures.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            findings: self.findings.load(Ordering::Relaxed),
+            unique_findings: self.unique_findings.load(Ordering::Relaxed),
+            corpus_size: self.corpus_size.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A point-in-time snapshot of [`Stats`], suitable for logging or
+/// serializing to `--stats-json`.
+#[derive(Serialize)]
+pub struct Snapshot {
+    pub elapsed_secs: f64,
+    pub total_execs: u64,
+    pub execs_per_sec: f64,
+    pub per_thread_execs: Vec<u64>,
+    pub per_thread_execs_per_sec: Vec<f64>,
+    pub parse_failures: u64,
+    pub timeouts: u64,
+    pub findings: u64,
+    pub unique_findings: u64,
+    pub corpus_size: u64,
+}
+
+/// Spawn a thread that periodically logs an aggregated dashboard and, if
+/// `stats_json` is set, writes the same snapshot there as JSON. The thread
+/// loops forever and never returns, so the enclosing `thread::scope` does
+/// not finish on its own, even after every worker thread has returned.
+pub fn spawn_reporter<'scope, 'env>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    stats: &'env Stats,
+    interval: Duration,
+    stats_json: Option<&'env Path>,
+) {
+    scope.spawn(move || {
+        loop {
+            std::thread::sleep(interval);
+            let snap = stats.snapshot();
+            info!(
+                "execs: {} total, {:.0}/sec overall | corpus: {} | findings: {} unique / {} total | timeouts: {} | elapsed: {:.0}s",
+                snap.total_execs,
+                snap.execs_per_sec,
+                snap.corpus_size,
+                snap.unique_findings,
+                snap.findings,
+                snap.timeouts,
+                snap.elapsed_secs,
+            );
+            debug!("per-thread execs/sec: {:?}", snap.per_thread_execs_per_sec);
+            if let Some(path) = stats_json {
+                if let Err(e) = write_snapshot(path, &snap) {
+                    warn!("Failed to write stats snapshot to {}: {e}", path.display());
+                }
+            }
+        }
+    });
+}
+
+fn write_snapshot(path: &Path, snap: &Snapshot) -> Result<()> {
+    let json = serde_json::to_string_pretty(snap).context("Failed to serialize stats snapshot")?;
+    fs::write(path, json).with_context(|| format!("Failed to write stats snapshot to {}", path.display()))
+}